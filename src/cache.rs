@@ -1,25 +1,87 @@
-use redis::{Client, Commands, RedisResult};
+use async_trait::async_trait;
+use bb8::Pool;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client, RedisError};
 use serde_json;
-use chrono::{Datelike, IsoWeek};
+use chrono::{Datelike, IsoWeek, Utc};
 
-pub struct RedisCache {
+const WEEK_TTL_SECS: u64 = 7 * 24 * 3600;
+
+// Rolling window for slots-history:* sorted sets, trimmed by ZREMRANGEBYSCORE
+const AVAILABILITY_HISTORY_WINDOW_SECS: i64 = 30 * 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassChangeKind {
+    Created,
+    Updated,
+    Cancelled,
+}
+
+impl ClassChangeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ClassChangeKind::Created => "created",
+            ClassChangeKind::Updated => "updated",
+            ClassChangeKind::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassChangeEvent {
+    pub class_id: i64,
+    pub kind: ClassChangeKind,
+}
+
+pub struct RedisConnectionManager {
     client: Client,
 }
 
-impl RedisCache {
-    pub fn new(redis_url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+impl RedisConnectionManager {
+    pub fn new(redis_url: &str) -> Result<Self, RedisError> {
         let client = Client::open(redis_url)?;
-        Ok(RedisCache { client })
+        Ok(RedisConnectionManager { client })
+    }
+}
+
+#[async_trait]
+impl bb8::ManageConnection for RedisConnectionManager {
+    type Connection = ConnectionManager;
+    type Error = RedisError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        self.client.get_connection_manager().await
+    }
+
+    async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        redis::cmd("PING").query_async(conn).await
+    }
+
+    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+        false
+    }
+}
+
+#[derive(Clone)]
+pub struct RedisCache {
+    pool: Pool<RedisConnectionManager>,
+}
+
+impl RedisCache {
+    pub async fn new(redis_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let manager = RedisConnectionManager::new(redis_url)?;
+        let pool = Pool::builder().build(manager).await?;
+        Ok(RedisCache { pool })
     }
 
-    pub fn store_schedule_with_week(
+    pub async fn store_schedule_with_week(
         &self,
         subdomain: &str,
         business_unit: u32,
         iso_week: &IsoWeek,
-        schedule: &[crate::FilteredClass],
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.client.get_connection()?;
+        schedule: &[crate::model::FilteredClass],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await?;
 
         let week_key = format!("schedule:{}:{}:{}-W{:02}",
                                subdomain,
@@ -30,26 +92,131 @@ impl RedisCache {
         let json_data = serde_json::to_string(schedule)?;
 
         // Store with 7 day expiration (until next week)
-        let _: () = conn.set_ex(&week_key, json_data, 7 * 24 * 3600)?;
+        let _: () = (*conn).set_ex(&week_key, json_data, WEEK_TTL_SECS).await?;
 
         println!("Stored schedule with key: {}", week_key);
         Ok(())
     }
 
-    pub fn store_class(
+    // Pipelines per-class SET EX plus a getset+expire on classhash:* to detect new/changed
+    // classes, then publishes any changes to schedule-events:{subdomain}:{bu}.
+    pub async fn store_classes(
         &self,
         subdomain: &str,
         business_unit: u32,
-        class: &crate::FilteredClass,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.client.get_connection()?;
+        classes: &[crate::model::FilteredClass],
+    ) -> Result<Vec<ClassChangeEvent>, Box<dyn std::error::Error + Send + Sync>> {
+        if classes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.pool.get().await?;
+
+        // Store the cancelled bit alongside the hash so we can tell a genuine
+        // not-cancelled -> cancelled transition apart from an unrelated change to an
+        // already-cancelled class.
+        let states: Vec<String> = classes
+            .iter()
+            .map(|class| format!("{}:{}", class.cancelled as u8, class.content_hash()))
+            .collect();
+
+        let mut pipe = redis::pipe();
+        for (class, state) in classes.iter().zip(&states) {
+            let class_key = format!("class:{}:{}:{}", subdomain, business_unit, class.id);
+            let class_json = serde_json::to_string(class)?;
+            let hash_key = format!("classhash:{}:{}:{}", subdomain, business_unit, class.id);
 
-        let class_key = format!("class:{}:{}:{}", subdomain, business_unit, class.id);
-        let class_json = serde_json::to_string(class)?;
+            pipe.set_ex(class_key, class_json, WEEK_TTL_SECS).ignore();
+            pipe.getset(hash_key.clone(), state.clone());
+            pipe.expire(hash_key, WEEK_TTL_SECS as i64).ignore();
+        }
 
-        // 7 days
-        let _: () = conn.set_ex(&class_key, class_json, 7 * 24 * 3600)?;
+        let previous_states: Vec<Option<String>> = pipe.query_async(&mut *conn).await?;
 
+        let mut events = Vec::new();
+        for ((class, state), previous_state) in classes.iter().zip(&states).zip(previous_states) {
+            let kind = match previous_state {
+                None => ClassChangeKind::Created,
+                Some(previous) if &previous != state => {
+                    let previous_cancelled = previous.starts_with("1:");
+                    if !previous_cancelled && class.cancelled {
+                        ClassChangeKind::Cancelled
+                    } else {
+                        ClassChangeKind::Updated
+                    }
+                }
+                Some(_) => continue,
+            };
+            events.push(ClassChangeEvent { class_id: class.id, kind });
+        }
+
+        if !events.is_empty() {
+            let channel = format!("schedule-events:{}:{}", subdomain, business_unit);
+            let mut publish_pipe = redis::pipe();
+            for event in &events {
+                let payload = serde_json::json!({
+                    "id": event.class_id,
+                    "event": event.kind.as_str(),
+                })
+                .to_string();
+                publish_pipe.publish(channel.clone(), payload).ignore();
+            }
+            publish_pipe.query_async::<()>(&mut *conn).await?;
+        }
+
+        Ok(events)
+    }
+
+    pub async fn record_availability(
+        &self,
+        subdomain: &str,
+        business_unit: u32,
+        classes: &[crate::model::FilteredClass],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if classes.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.pool.get().await?;
+        let now = Utc::now().timestamp();
+
+        let mut pipe = redis::pipe();
+        for class in classes {
+            let available = class.slots.available.unwrap_or(0);
+            let key = format!("slots-history:{}:{}:{}", subdomain, business_unit, class.id);
+
+            // Member must be unique per sample (sorted sets de-dupe by member), so encode
+            // the timestamp into it rather than using the repeat-prone `available` count.
+            let member = format!("{}:{}", now, available);
+            pipe.zadd(key.clone(), member, now).ignore();
+            pipe.zrembyscore(key, 0, now - AVAILABILITY_HISTORY_WINDOW_SECS).ignore();
+        }
+
+        pipe.query_async::<()>(&mut *conn).await?;
         Ok(())
     }
+
+    // Returns (timestamp, available) samples for a class over the last `hours` hours
+    pub async fn availability_trajectory(
+        &self,
+        subdomain: &str,
+        business_unit: u32,
+        class_id: i64,
+        hours: i64,
+    ) -> Result<Vec<(i64, i64)>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.pool.get().await?;
+        let key = format!("slots-history:{}:{}:{}", subdomain, business_unit, class_id);
+        let since = Utc::now().timestamp() - hours * 3600;
+
+        // Members are "{timestamp}:{available}"; the score (timestamp) is used for the
+        // range query, the available count is read back out of the member itself.
+        let samples: Vec<(String, i64)> = (*conn).zrangebyscore_withscores(key, since, "+inf").await?;
+        Ok(samples
+            .into_iter()
+            .filter_map(|(member, timestamp)| {
+                let available = member.split(':').nth(1)?.parse().ok()?;
+                Some((timestamp, available))
+            })
+            .collect())
+    }
 }
@@ -1,19 +1,23 @@
 use clap::Parser;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::Write;
-use chrono::{Datelike, IsoWeek, NaiveDate, Utc};
+use std::time::Duration;
+use chrono::{Datelike, IsoWeek, NaiveDate, Utc, Weekday};
 
 mod cache;
+mod model;
+mod search;
 
 use cache::RedisCache;
+use model::FilteredClass;
+use search::SearchIndex;
 
 #[derive(Parser)]
 #[command(name = "rezervo-cache")]
-#[command(about = "Fetch class schedule for the current ISO week")]
+#[command(about = "Fetch class schedule for the current (and optionally arbitrary) ISO week(s)")]
 struct Args {
     #[arg(short, long)]
     subdomain: String,
@@ -23,106 +27,143 @@ struct Args {
 
     #[arg(long, default_value = "redis://redis:6379")]
     redis_url: String,
-}
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct FilteredClass {
-    #[serde(rename = "bookableEarliest")]
-    pub bookable_earliest: String,
-    #[serde(rename = "bookableLatest")]
-    pub bookable_latest: String,
-    pub id: i64,
-    pub name: String,
-    pub duration: Value,
-    #[serde(rename = "groupActivityProduct")]
-    pub group_activity_product: Value,
-    #[serde(rename = "businessUnit")]
-    pub business_unit: Value,
-    pub locations: Vec<Value>,
-    pub instructors: Vec<Value>,
-    #[serde(rename = "externalMessage")]
-    pub external_message: Option<String>,
-    pub cancelled: bool,
-    pub slots: Value,
+    /// ISO week to fetch, e.g. `2025-W12`, or a convenience token (`next`, `tomorrow`).
+    /// Defaults to the current week (see `--weeks` for the default range).
+    #[arg(long)]
+    week: Option<String>,
+
+    /// Number of consecutive weeks to fetch starting from `--week` (or the current week
+    /// if `--week` is omitted). Defaults to 2 (current + next) when neither flag is given.
+    #[arg(long)]
+    weeks: Option<u32>,
+
+    /// Base URL of an external search index (e.g. Meilisearch) to upsert cached classes
+    /// into. Opt-in: when omitted, no search indexing happens.
+    #[arg(long)]
+    search_url: Option<String>,
+
+    /// Print the availability history for a cached class instead of fetching a schedule.
+    #[arg(long)]
+    trajectory_class: Option<i64>,
+
+    /// Hours of history to show with `--trajectory-class`.
+    #[arg(long, default_value_t = 24)]
+    trajectory_hours: i64,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let redis_cache = RedisCache::new(&args.redis_url)?;
+    let redis_cache = RedisCache::new(&args.redis_url).await?;
+
+    if let Some(class_id) = args.trajectory_class {
+        let samples = redis_cache
+            .availability_trajectory(&args.subdomain, args.business_unit, class_id, args.trajectory_hours)
+            .await?;
+        for (timestamp, available) in samples {
+            println!("{} {}", timestamp, available);
+        }
+        return Ok(());
+    }
+
+    let search_index = args.search_url.as_deref().map(SearchIndex::new);
+
+    // Default to the current week when neither `--week` nor `--weeks` is given, and keep
+    // the historical default of also fetching next week (i.e. 2 consecutive weeks).
+    let start_date = match &args.week {
+        Some(week) => parse_week_arg(week)?,
+        None => Utc::now().date_naive(),
+    };
+    let week_count = args.weeks.unwrap_or(if args.week.is_none() { 2 } else { 1 });
+
+    let weeks: Vec<(NaiveDate, NaiveDate, IsoWeek)> = (0..week_count)
+        .map(|i| week_bounds(start_date + chrono::Duration::weeks(i as i64)))
+        .collect();
+
+    // Fetch every requested week concurrently rather than one after another
+    let mut fetches = tokio::task::JoinSet::new();
+    for (week_start, week_end, iso_week) in weeks {
+        let redis_cache = redis_cache.clone();
+        let search_index = search_index.clone();
+        let subdomain = args.subdomain.clone();
+        let business_unit = args.business_unit;
+        fetches.spawn(async move {
+            fetch_and_cache_week(&redis_cache, search_index.as_ref(), &subdomain, business_unit, week_start, week_end, iso_week).await
+        });
+    }
 
-    // Get current and next ISO week dates
-    let (current_week_start, current_week_end, current_iso_week) = get_current_iso_week();
-    let (next_week_start, next_week_end, next_iso_week) = get_next_iso_week();
+    while let Some(result) = fetches.join_next().await {
+        result??;
+    }
 
-    println!("Fetching current week {} ({} to {})",
-             format_iso_week(&current_iso_week), current_week_start, current_week_end);
-    println!("Fetching next week {} ({} to {})",
-             format_iso_week(&next_iso_week), next_week_start, next_week_end);
+    Ok(())
+}
 
-    // Fetch current week
-    let current_schedule = fetch_brp_schedule_for_week(&args.subdomain, args.business_unit, current_week_start, current_week_end).await?;
+// Shared by the default range, --week and --weeks code paths
+async fn fetch_and_cache_week(
+    redis_cache: &RedisCache,
+    search_index: Option<&SearchIndex>,
+    subdomain: &str,
+    business_unit: u32,
+    week_start: NaiveDate,
+    week_end: NaiveDate,
+    iso_week: IsoWeek,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!("Fetching week {} ({} to {})", format_iso_week(&iso_week), week_start, week_end);
 
-    // Fetch next week
-    let next_schedule = fetch_brp_schedule_for_week(&args.subdomain, args.business_unit, next_week_start, next_week_end).await?;
+    let schedule = fetch_brp_schedule_for_week(subdomain, business_unit, week_start, week_end).await?;
 
-    // Store current week
-    if let Err(e) = redis_cache.store_schedule_with_week(&args.subdomain, args.business_unit, &current_iso_week, &current_schedule) {
-        eprintln!("Warning: Failed to store current week schedule: {}", e);
+    if let Err(e) = redis_cache.store_schedule_with_week(subdomain, business_unit, &iso_week, &schedule).await {
+        eprintln!("Warning: Failed to store {} schedule: {}", format_iso_week(&iso_week), e);
     } else {
-        println!("Current week schedule cached successfully");
+        println!("{} schedule cached successfully", format_iso_week(&iso_week));
     }
 
-    for class in &current_schedule {
-        if let Err(e) = redis_cache.store_class(&args.subdomain, args.business_unit, class) {
-            eprintln!("Warning: Failed to store current week class {}: {}", class.id, e);
-        }
+    match redis_cache.store_classes(subdomain, business_unit, &schedule).await {
+        Ok(events) => println!("Detected {} change(s) in {}", events.len(), format_iso_week(&iso_week)),
+        Err(e) => eprintln!("Warning: Failed to store {} classes: {}", format_iso_week(&iso_week), e),
     }
 
-    // Store next week
-    if let Err(e) = redis_cache.store_schedule_with_week(&args.subdomain, args.business_unit, &next_iso_week, &next_schedule) {
-        eprintln!("Warning: Failed to store next week schedule: {}", e);
-    } else {
-        println!("Next week schedule cached successfully");
+    if let Err(e) = redis_cache.record_availability(subdomain, business_unit, &schedule).await {
+        eprintln!("Warning: Failed to record availability history for {}: {}", format_iso_week(&iso_week), e);
     }
 
-    for class in &next_schedule {
-        if let Err(e) = redis_cache.store_class(&args.subdomain, args.business_unit, class) {
-            eprintln!("Warning: Failed to store next week class {}: {}", class.id, e);
+    if let Some(index) = search_index {
+        if let Err(e) = index.index_classes(&schedule, &format_iso_week(&iso_week)).await {
+            eprintln!("Warning: Failed to index {} classes for search: {}", format_iso_week(&iso_week), e);
         }
     }
 
-    println!("Successfully cached {} classes for current week {}",
-             current_schedule.len(), format_iso_week(&current_iso_week));
-    println!("Successfully cached {} classes for next week {}",
-             next_schedule.len(), format_iso_week(&next_iso_week));
-
+    println!("Successfully cached {} classes for week {}", schedule.len(), format_iso_week(&iso_week));
     Ok(())
 }
 
-fn get_next_iso_week() -> (NaiveDate, NaiveDate, IsoWeek) {
+fn parse_week_arg(value: &str) -> Result<NaiveDate, Box<dyn std::error::Error>> {
     let today = Utc::now().date_naive();
-    let next_week_date = today + chrono::Duration::days(7);
-    let iso_week = next_week_date.iso_week();
-
-    // Calculate Monday (start of ISO week)
-    let days_from_monday = next_week_date.weekday().num_days_from_monday();
-    let week_start = next_week_date - chrono::Duration::days(days_from_monday as i64);
-
-    // Calculate Sunday (end of ISO week)
-    let week_end = week_start + chrono::Duration::days(6);
-
-    (week_start, week_end, iso_week)
+    match value.to_lowercase().as_str() {
+        "current" | "this" => Ok(today),
+        "next" => Ok(today + chrono::Duration::days(7)),
+        "tomorrow" => Ok(today + chrono::Duration::days(1)),
+        _ => {
+            let (year_str, week_str) = value.split_once("-W").ok_or_else(|| {
+                format!("Invalid --week '{}', expected YYYY-Www (e.g. 2025-W12) or next/tomorrow", value)
+            })?;
+            let year: i32 = year_str.parse()?;
+            let week: u32 = week_str.parse()?;
+            NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)
+                .ok_or_else(|| format!("Invalid ISO week '{}'", value).into())
+        }
+    }
 }
 
-fn get_current_iso_week() -> (NaiveDate, NaiveDate, IsoWeek) {
-    let today = Utc::now().date_naive();
-    let iso_week = today.iso_week();
+fn week_bounds(date: NaiveDate) -> (NaiveDate, NaiveDate, IsoWeek) {
+    let iso_week = date.iso_week();
 
     // Calculate Monday (start of ISO week)
-    let days_from_monday = today.weekday().num_days_from_monday();
-    let week_start = today - chrono::Duration::days(days_from_monday as i64);
+    let days_from_monday = date.weekday().num_days_from_monday();
+    let week_start = date - chrono::Duration::days(days_from_monday as i64);
 
     // Calculate Sunday (end of ISO week)
     let week_end = week_start + chrono::Duration::days(6);
@@ -134,54 +175,107 @@ fn format_iso_week(iso_week: &IsoWeek) -> String {
     format!("{}-W{:02}", iso_week.year(), iso_week.week())
 }
 
+const PAGE_SIZE: u32 = 100;
+const MAX_RETRIES: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
 async fn fetch_brp_schedule_for_week(
     subdomain: &str,
     business_unit: u32,
     week_start: NaiveDate,
     week_end: NaiveDate,
-) -> Result<Vec<FilteredClass>, Box<dyn std::error::Error>> {
+) -> Result<Vec<FilteredClass>, Box<dyn std::error::Error + Send + Sync>> {
     let client = Client::new();
     let mut classes = Vec::new();
     let mut seen_ids = HashSet::new();
 
-    // Fetch the entire week in one request
     let url = format!(
         "https://{}.brpsystems.com/brponline/api/ver3/businessunits/{}/groupactivities",
         subdomain, business_unit
     );
 
-    let params = [
-        ("period.start", format!("{}T00:00:00.000Z", week_start)),
-        ("period.end", format!("{}T23:59:59.999Z", week_end)),
-    ];
-
     println!("Fetching from: {}", url);
     println!("Period: {} to {}", week_start, week_end);
 
-    let response = client.get(&url).query(&params).send().await?;
+    // Page through the endpoint since large business units don't fit in a single response
+    let mut skip = 0u32;
+    loop {
+        let params = [
+            ("period.start", format!("{}T00:00:00.000Z", week_start)),
+            ("period.end", format!("{}T23:59:59.999Z", week_end)),
+            ("count", PAGE_SIZE.to_string()),
+            ("skip", skip.to_string()),
+        ];
+
+        let items = fetch_schedule_page_with_retry(&client, &url, &params).await?;
+        let page_len = items.len();
+        println!("Received {} items from API (skip={})", page_len, skip);
+
+        for item in items {
+            if let Some(id) = item.get("id").and_then(|v| v.as_i64()) {
+                let id_string = id.to_string();
+                if !seen_ids.contains(&id_string) {
+                    seen_ids.insert(id_string);
+
+                    if item.get("bookableEarliest").is_some() && item.get("bookableLatest").is_some() {
+                        if let Ok(filtered_class) = serde_json::from_value::<FilteredClass>(item) {
+                            classes.push(filtered_class);
+                        }
+                    }
+                }
+            }
+        }
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to fetch schedule: {}", response.status()).into());
+        if page_len < PAGE_SIZE as usize {
+            break;
+        }
+        skip += PAGE_SIZE;
     }
 
-    let items: Vec<Value> = response.json().await?;
-    println!("Received {} items from API", items.len());
+    println!("Filtered to {} unique classes", classes.len());
+    Ok(classes)
+}
 
-    for item in items {
-        if let Some(id) = item.get("id").and_then(|v| v.as_i64()) {
-            let id_string = id.to_string();
-            if !seen_ids.contains(&id_string) {
-                seen_ids.insert(id_string);
+// Retries with exponential backoff on 429/5xx and network errors
+async fn fetch_schedule_page_with_retry(
+    client: &Client,
+    url: &str,
+    params: &[(&str, String)],
+) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut attempt = 0;
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        attempt += 1;
+        match client.get(url).query(params).send().await {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(response.json().await?);
+                }
 
-                if item.get("bookableEarliest").is_some() && item.get("bookableLatest").is_some() {
-                    if let Ok(filtered_class) = serde_json::from_value::<FilteredClass>(item) {
-                        classes.push(filtered_class);
-                    }
+                let retryable = status.as_u16() == 429 || status.is_server_error();
+                if retryable && attempt <= MAX_RETRIES {
+                    eprintln!(
+                        "Warning: transient error fetching schedule page ({}), retrying in {:?} (attempt {}/{})",
+                        status, backoff, attempt, MAX_RETRIES
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    continue;
                 }
+
+                return Err(format!("Failed to fetch schedule: {}", status).into());
+            }
+            Err(e) if attempt <= MAX_RETRIES => {
+                eprintln!(
+                    "Warning: network error fetching schedule page ({}), retrying in {:?} (attempt {}/{})",
+                    e, backoff, attempt, MAX_RETRIES
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
             }
+            Err(e) => return Err(e.into()),
         }
     }
-
-    println!("Filtered to {} unique classes", classes.len());
-    Ok(classes)
 }
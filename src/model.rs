@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FilteredClass {
+    #[serde(rename = "bookableEarliest")]
+    pub bookable_earliest: String,
+    #[serde(rename = "bookableLatest")]
+    pub bookable_latest: String,
+    pub id: i64,
+    pub name: String,
+    pub duration: Duration,
+    #[serde(rename = "groupActivityProduct")]
+    pub group_activity_product: GroupActivityProduct,
+    #[serde(rename = "businessUnit")]
+    pub business_unit: BusinessUnit,
+    pub locations: Vec<Location>,
+    pub instructors: Vec<Instructor>,
+    #[serde(rename = "externalMessage")]
+    pub external_message: Option<String>,
+    pub cancelled: bool,
+    pub slots: Slots,
+}
+
+impl FilteredClass {
+    // Used to detect whether a class changed between runs; only covers the fields
+    // consumers care about, not e.g. `name`.
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.bookable_earliest.as_bytes());
+        hasher.update(self.bookable_latest.as_bytes());
+        hasher.update([self.cancelled as u8]);
+        hasher.update(self.slots.canonical_json().as_bytes());
+        if let Some(message) = &self.external_message {
+            hasher.update(message.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+// e.g. `{"value": 60, "unit": "minutes"}`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Duration {
+    pub value: Option<i64>,
+    pub unit: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GroupActivityProduct {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BusinessUnit {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Location {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Instructor {
+    pub id: Option<i64>,
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+// e.g. `{"total": 20, "reserved": 17, "available": 3}`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Slots {
+    pub total: Option<i64>,
+    pub reserved: Option<i64>,
+    pub available: Option<i64>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+impl Slots {
+    // BTreeMap renders in sorted key order, unlike HashMap whose iteration order is
+    // randomized per-process, so this stays stable across runs for content_hash().
+    fn canonical_json(&self) -> String {
+        let mut fields: BTreeMap<String, Value> = self.extra.clone().into_iter().collect();
+        fields.insert("total".to_string(), serde_json::json!(self.total));
+        fields.insert("reserved".to_string(), serde_json::json!(self.reserved));
+        fields.insert("available".to_string(), serde_json::json!(self.available));
+        serde_json::to_string(&fields).unwrap_or_default()
+    }
+}
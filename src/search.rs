@@ -0,0 +1,72 @@
+use reqwest::Client;
+use serde::Serialize;
+
+use crate::model::FilteredClass;
+
+#[derive(Serialize)]
+struct ClassDocument {
+    id: i64,
+    name: String,
+    instructors: Vec<String>,
+    locations: Vec<String>,
+    #[serde(rename = "bookableEarliest")]
+    bookable_earliest: String,
+    #[serde(rename = "bookableLatest")]
+    bookable_latest: String,
+    week: String,
+    cancelled: bool,
+}
+
+impl ClassDocument {
+    fn from_class(class: &FilteredClass, week: &str) -> Self {
+        ClassDocument {
+            id: class.id,
+            name: class.name.clone(),
+            instructors: class.instructors.iter().filter_map(|i| i.name.clone()).collect(),
+            locations: class.locations.iter().filter_map(|l| l.name.clone()).collect(),
+            bookable_earliest: class.bookable_earliest.clone(),
+            bookable_latest: class.bookable_latest.clone(),
+            week: week.to_string(),
+            cancelled: class.cancelled,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchIndex {
+    client: Client,
+    search_url: String,
+}
+
+impl SearchIndex {
+    pub fn new(search_url: &str) -> Self {
+        SearchIndex {
+            client: Client::new(),
+            search_url: search_url.to_string(),
+        }
+    }
+
+    pub async fn index_classes(
+        &self,
+        classes: &[FilteredClass],
+        week: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if classes.is_empty() {
+            return Ok(());
+        }
+
+        let documents: Vec<ClassDocument> = classes
+            .iter()
+            .map(|class| ClassDocument::from_class(class, week))
+            .collect();
+
+        let url = format!("{}/indexes/classes/documents", self.search_url);
+        let response = self.client.post(&url).json(&documents).send().await?;
+
+        if !response.status().is_success() {
+            return Err(format!("Failed to index classes: {}", response.status()).into());
+        }
+
+        Ok(())
+    }
+}